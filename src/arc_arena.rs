@@ -0,0 +1,246 @@
+//! An arena-backed cactus stack for multi-threaded programs.
+//!
+//! As with [`CactusArena`](../rc_arena/struct.CactusArena.html), an `ArcCactusArena` owns all of
+//! its nodes in one `Vec` and hands out cheap, `Copy` [`Handle`]s into it, so that whole families
+//! of discarded states can be freed in a single deallocation instead of one `Arc` refcount drop
+//! per chunk of nodes. Because the arena may be shared between threads, its storage sits behind a `Mutex`;
+//! reading a value therefore clones it out of the lock rather than returning a reference.
+
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+static NEXT_ARENA_ID: AtomicUsize = AtomicUsize::new(0);
+
+struct Node<T> {
+    val: T,
+    parent: Option<usize>,
+}
+
+/// An arena owning a collection of cactus stack nodes, safe to share between threads.
+pub struct ArcCactusArena<T> {
+    id: usize,
+    nodes: Mutex<Vec<Node<T>>>,
+}
+
+impl<T> ArcCactusArena<T> {
+    /// Create a new, empty arena.
+    pub fn new() -> Self {
+        ArcCactusArena {
+            id: NEXT_ARENA_ID.fetch_add(1, Ordering::Relaxed),
+            nodes: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Return a handle to this arena's (empty) root node.
+    ///
+    /// ```
+    /// use cactus::ArcCactusArena;
+    /// let arena = ArcCactusArena::new();
+    /// let root = arena.root();
+    /// assert!(root.is_empty());
+    /// let c1 = root.child(&arena, 1);
+    /// assert_eq!(c1.val(&arena), Some(1));
+    /// ```
+    pub fn root(&self) -> Handle<T> {
+        Handle {
+            arena_id: self.id,
+            index: None,
+            phantom: PhantomData,
+        }
+    }
+
+    fn check(&self, h: Handle<T>) {
+        assert_eq!(
+            h.arena_id, self.id,
+            "handle does not belong to this arena"
+        );
+    }
+
+    /// Reclaim every node not reachable from `live`, compacting the arena's storage. Returns the
+    /// handles in `live` updated to refer to their (possibly moved) new locations; any other
+    /// handle obtained from this arena before the call is invalidated.
+    pub fn gc(&self, live: &[Handle<T>]) -> Vec<Handle<T>> {
+        for h in live {
+            self.check(*h);
+        }
+        let mut nodes = self.nodes.lock().unwrap();
+        let mut reachable = vec![false; nodes.len()];
+        for h in live {
+            let mut cur = h.index;
+            while let Some(idx) = cur {
+                if reachable[idx] {
+                    break;
+                }
+                reachable[idx] = true;
+                cur = nodes[idx].parent;
+            }
+        }
+        let mut remap = vec![None; nodes.len()];
+        let mut new_nodes = Vec::new();
+        for (old_idx, node) in nodes.drain(..).enumerate() {
+            if reachable[old_idx] {
+                remap[old_idx] = Some(new_nodes.len());
+                let new_parent = node
+                    .parent
+                    .map(|p| remap[p].expect("a reachable node's parent must also be reachable"));
+                new_nodes.push(Node {
+                    val: node.val,
+                    parent: new_parent,
+                });
+            }
+        }
+        *nodes = new_nodes;
+        live.iter()
+            .map(|h| Handle {
+                arena_id: h.arena_id,
+                index: h.index.map(|i| remap[i].unwrap()),
+                phantom: PhantomData,
+            })
+            .collect()
+    }
+}
+
+impl<T> Default for ArcCactusArena<T> {
+    fn default() -> Self {
+        ArcCactusArena::new()
+    }
+}
+
+/// A cheap, `Copy` handle to a node owned by an [`ArcCactusArena`]. Every method that takes an
+/// arena panics if given a handle from a different arena.
+pub struct Handle<T> {
+    arena_id: usize,
+    index: Option<usize>,
+    phantom: PhantomData<T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.arena_id == other.arena_id && self.index == other.index
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Handle<T> {
+    /// Is this handle's node empty (i.e. the arena's root)?
+    pub fn is_empty(&self) -> bool {
+        self.index.is_none()
+    }
+
+    /// Create a new node which is a child of this node.
+    pub fn child(&self, arena: &ArcCactusArena<T>, v: T) -> Handle<T> {
+        arena.check(*self);
+        let mut nodes = arena.nodes.lock().unwrap();
+        nodes.push(Node {
+            val: v,
+            parent: self.index,
+        });
+        Handle {
+            arena_id: self.arena_id,
+            index: Some(nodes.len() - 1),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Return this node's parent, or `None` if this node is empty.
+    pub fn parent(&self, arena: &ArcCactusArena<T>) -> Option<Handle<T>> {
+        arena.check(*self);
+        let idx = self.index?;
+        let nodes = arena.nodes.lock().unwrap();
+        Some(Handle {
+            arena_id: self.arena_id,
+            index: nodes[idx].parent,
+            phantom: PhantomData,
+        })
+    }
+
+    /// How many values are there in this stack?
+    pub fn len(&self, arena: &ArcCactusArena<T>) -> usize {
+        arena.check(*self);
+        let nodes = arena.nodes.lock().unwrap();
+        let mut n = 0;
+        let mut cur = self.index;
+        while let Some(idx) = cur {
+            n += 1;
+            cur = nodes[idx].parent;
+        }
+        n
+    }
+}
+
+impl<T: Clone> Handle<T> {
+    /// Return a clone of this node's value, or `None` if this node is empty.
+    pub fn val(&self, arena: &ArcCactusArena<T>) -> Option<T> {
+        arena.check(*self);
+        let nodes = arena.nodes.lock().unwrap();
+        self.index.map(|idx| nodes[idx].val.clone())
+    }
+
+    /// Return this node's values, from the top of the stack to the bottom.
+    pub fn vals(&self, arena: &ArcCactusArena<T>) -> Vec<T> {
+        arena.check(*self);
+        let nodes = arena.nodes.lock().unwrap();
+        let mut out = Vec::new();
+        let mut cur = self.index;
+        while let Some(idx) = cur {
+            out.push(nodes[idx].val.clone());
+            cur = nodes[idx].parent;
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gc_reclaims_dead_sub_stacks_and_remaps_live_handles() {
+        let arena = ArcCactusArena::new();
+        let root = arena.root();
+        let live_branch = root.child(&arena, 1).child(&arena, 2);
+        // A sibling sub-stack that will not be passed to `gc`, so it should be reclaimed.
+        let _dead_branch = root.child(&arena, 10).child(&arena, 11).child(&arena, 12);
+        let live_leaf = live_branch.child(&arena, 3);
+
+        let remapped = arena.gc(&[live_branch, live_leaf]);
+        let new_branch = remapped[0];
+        let new_leaf = remapped[1];
+
+        assert_eq!(new_branch.vals(&arena), [2, 1]);
+        assert_eq!(new_branch.len(&arena), 2);
+        assert_eq!(new_leaf.vals(&arena), [3, 2, 1]);
+        assert_eq!(new_leaf.len(&arena), 3);
+        assert!(new_leaf.parent(&arena) == Some(new_branch));
+
+        // Only the live sub-stack's three nodes survive the compaction; the dead branch's three
+        // nodes are reclaimed.
+        assert_eq!(arena.nodes.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn gc_invalidates_handles_not_passed_as_live() {
+        let arena = ArcCactusArena::new();
+        let root = arena.root();
+        let keep = root.child(&arena, 1);
+        let stale = root.child(&arena, 2).child(&arena, 3);
+
+        arena.gc(&[keep]);
+
+        // `stale` was not kept alive by the `gc` call above, so using it against the
+        // post-compaction arena is out of bounds, as the documented "invalidated" semantics
+        // promise.
+        stale.len(&arena);
+    }
+}
@@ -0,0 +1,508 @@
+//! The `Arc`-based cactus stack, suited to multi-threaded programs.
+//!
+//! As with the `Rc`-based [`Cactus`](../rc_cactus/struct.Cactus.html), nodes are packed into
+//! fixed-size chunks so that a long, non-branching stack stays contiguous in memory. Since a
+//! chunk can be shared between threads, claiming a slot to extend it in place uses an atomic
+//! compare-and-set rather than a plain `Cell`.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The number of elements packed into a single chunk allocation.
+const CHUNK_SIZE: usize = 16;
+
+/// An immutable parent pointer tree node ("cactus stack"). See the
+/// [module documentation](index.html) for more details.
+pub struct Cactus<T>(Option<Inner<T>>);
+
+struct Inner<T> {
+    chunk: Arc<Chunk<T>>,
+    /// The index within `chunk` that this handle's logical top occupies.
+    index: usize,
+}
+
+impl<T> Clone for Inner<T> {
+    fn clone(&self) -> Self {
+        Inner {
+            chunk: self.chunk.clone(),
+            index: self.index,
+        }
+    }
+}
+
+/// A single allocation holding up to `CHUNK_SIZE` values, shareable between threads. A handle's `index`
+/// names its position within `slots`; slots are written at most once (the "write-once" invariant:
+/// a given `(chunk, index)` pair always denotes the same value) and are claimed via an atomic
+/// compare-and-set on `high_water`.
+struct Chunk<T> {
+    parent: Cactus<T>,
+    /// The length of `parent` -- i.e. how many values lie below slot 0 of this chunk.
+    base_len: usize,
+    /// One past the highest slot index claimed so far.
+    high_water: AtomicUsize,
+    slots: [UnsafeCell<MaybeUninit<T>>; CHUNK_SIZE],
+}
+
+// Safe because access to the slots is only ever granted through `&T` (via `get`, gated by
+// `high_water`) or a single write-once `write` (gated by winning the `compare_exchange` in
+// `try_extend`), so the usual `Send`/`Sync` requirements on `T` suffice.
+unsafe impl<T: Send> Send for Chunk<T> {}
+unsafe impl<T: Send + Sync> Sync for Chunk<T> {}
+
+impl<T> Chunk<T> {
+    fn new(parent: Cactus<T>, v: T) -> Self {
+        let base_len = parent.len();
+        let slots: [UnsafeCell<MaybeUninit<T>>; CHUNK_SIZE] =
+            [(); CHUNK_SIZE].map(|_| UnsafeCell::new(MaybeUninit::uninit()));
+        unsafe {
+            (*slots[0].get()).write(v);
+        }
+        Chunk {
+            parent,
+            base_len,
+            high_water: AtomicUsize::new(1),
+            slots,
+        }
+    }
+
+    /// Tries to claim slot `index + 1` for `v`. Fails (returning `v` back) if another child has
+    /// already claimed that slot or the chunk is full, in which case the caller must start a new
+    /// chunk instead.
+    fn try_extend(&self, index: usize, v: T) -> Result<(), T> {
+        let next = index + 1;
+        if next >= CHUNK_SIZE {
+            return Err(v);
+        }
+        if self
+            .high_water
+            .compare_exchange(next, next + 1, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Err(v);
+        }
+        unsafe {
+            (*self.slots[next].get()).write(v);
+        }
+        Ok(())
+    }
+
+    fn get(&self, index: usize) -> &T {
+        debug_assert!(index < self.high_water.load(Ordering::SeqCst));
+        unsafe { (*self.slots[index].get()).assume_init_ref() }
+    }
+}
+
+impl<T> Drop for Chunk<T> {
+    fn drop(&mut self) {
+        for i in 0..*self.high_water.get_mut() {
+            unsafe {
+                ptr::drop_in_place(self.slots[i].get_mut().as_mut_ptr());
+            }
+        }
+    }
+}
+
+impl<T> Cactus<T> {
+    /// Create an empty cactus stack.
+    ///
+    /// ```
+    /// use cactus::ArcCactus;
+    /// let c = ArcCactus::<u8>::new();
+    /// assert!(c.is_empty());
+    /// ```
+    pub fn new() -> Self {
+        Cactus(None)
+    }
+
+    /// Is this cactus empty?
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Create a new node which is a child of this node.
+    pub fn child(&self, v: T) -> Cactus<T> {
+        match &self.0 {
+            None => Cactus(Some(Inner {
+                chunk: Arc::new(Chunk::new(Cactus::new(), v)),
+                index: 0,
+            })),
+            Some(inner) => match inner.chunk.try_extend(inner.index, v) {
+                Ok(()) => Cactus(Some(Inner {
+                    chunk: inner.chunk.clone(),
+                    index: inner.index + 1,
+                })),
+                Err(v) => Cactus(Some(Inner {
+                    chunk: Arc::new(Chunk::new(self.clone(), v)),
+                    index: 0,
+                })),
+            },
+        }
+    }
+
+    /// Return this node's parent, or `None` if this node is empty.
+    pub fn parent(&self) -> Option<Cactus<T>> {
+        self.0.as_ref().map(|inner| {
+            if inner.index == 0 {
+                inner.chunk.parent.clone()
+            } else {
+                Cactus(Some(Inner {
+                    chunk: inner.chunk.clone(),
+                    index: inner.index - 1,
+                }))
+            }
+        })
+    }
+
+    /// Return a reference to this node's value, or `None` if this node is empty.
+    pub fn val(&self) -> Option<&T> {
+        self.0.as_ref().map(|inner| inner.chunk.get(inner.index))
+    }
+
+    /// How many values are there in this stack?
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            None => 0,
+            Some(inner) => inner.chunk.base_len + inner.index + 1,
+        }
+    }
+
+    /// Return an iterator over this node's values, from the top of the stack to the bottom.
+    pub fn vals(&self) -> Vals<'_, T> {
+        Vals {
+            cur: self.clone(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Return the deepest node shared by `self` and `other`, or `None` if the two stacks have no
+    /// common ancestor.
+    ///
+    /// ```
+    /// use cactus::ArcCactus;
+    /// let c = ArcCactus::new().child(1);
+    /// let c2 = c.child(2);
+    /// let c3 = c.child(3);
+    /// assert_eq!(c2.lca(&c3), Some(c));
+    /// ```
+    pub fn lca(&self, other: &Cactus<T>) -> Option<Cactus<T>> {
+        let mut a = self.clone();
+        let mut b = other.clone();
+        let mut a_len = a.len();
+        let mut b_len = b.len();
+        while a_len > b_len {
+            a = a.parent().unwrap();
+            a_len -= 1;
+        }
+        while b_len > a_len {
+            b = b.parent().unwrap();
+            b_len -= 1;
+        }
+        while a != b {
+            a = a.parent().unwrap();
+            b = b.parent().unwrap();
+        }
+        if a.is_empty() {
+            None
+        } else {
+            Some(a)
+        }
+    }
+
+    /// The length of the common suffix `self` and `other` share, i.e. `self.lca(other).len()`.
+    pub fn shared_len(&self, other: &Cactus<T>) -> usize {
+        self.lca(other).map_or(0, |c| c.len())
+    }
+
+    /// Try to convert this cactus into a [`NeCactus`], which statically guarantees it has a
+    /// value at its top. Returns the original (empty) cactus back in `Err` if it has none.
+    ///
+    /// ```
+    /// use cactus::ArcCactus;
+    /// let c = ArcCactus::new().child(1);
+    /// let nec = c.into_nonempty().unwrap();
+    /// assert_eq!(*nec, 1);
+    /// ```
+    pub fn into_nonempty(self) -> Result<NeCactus<T>, Cactus<T>> {
+        match self.0 {
+            Some(inner) => Ok(NeCactus(inner)),
+            None => Err(Cactus(None)),
+        }
+    }
+}
+
+impl<T> Default for Cactus<T> {
+    fn default() -> Self {
+        Cactus::new()
+    }
+}
+
+impl<T> Clone for Cactus<T> {
+    fn clone(&self) -> Self {
+        Cactus(self.0.clone())
+    }
+}
+
+impl<T> PartialEq for Cactus<T> {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (None, None) => true,
+            (Some(a), Some(b)) => a.index == b.index && Arc::ptr_eq(&a.chunk, &b.chunk),
+            _ => false,
+        }
+    }
+}
+
+impl<T> Eq for Cactus<T> {}
+
+impl<T: fmt::Debug> fmt::Debug for Cactus<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cactus {{ ")?;
+        let mut first = true;
+        for v in self.vals() {
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}", v)?;
+            first = false;
+        }
+        write!(f, " }}")
+    }
+}
+
+/// An iterator over the values of an `ArcCactus`, from top to bottom. Created by
+/// [`Cactus::vals`](struct.Cactus.html#method.vals).
+pub struct Vals<'a, T> {
+    cur: Cactus<T>,
+    phantom: PhantomData<&'a T>,
+}
+
+impl<'a, T> Iterator for Vals<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let inner = self.cur.0.as_ref()?;
+        let val = inner.chunk.get(inner.index) as *const T;
+        self.cur = self.cur.parent().unwrap();
+        // Safe because the original `Cactus` that `vals` was called on keeps every chunk in this
+        // stack's parent chain alive for at least as long as `'a`, regardless of where `self.cur`
+        // has since moved on to.
+        Some(unsafe { &*val })
+    }
+}
+
+/// A cactus stack node which is statically guaranteed to have a value at its top, eliminating the
+/// need to unwrap [`Cactus::val`]. Obtained via [`Cactus::into_nonempty`] or [`NeCactus::child`].
+/// Derefs to its top value.
+pub struct NeCactus<T>(Inner<T>);
+
+impl<T> NeCactus<T> {
+    /// Create a new node which is a child of this node.
+    pub fn child(&self, v: T) -> NeCactus<T> {
+        match self.0.chunk.try_extend(self.0.index, v) {
+            Ok(()) => NeCactus(Inner {
+                chunk: self.0.chunk.clone(),
+                index: self.0.index + 1,
+            }),
+            Err(v) => NeCactus(Inner {
+                chunk: Arc::new(Chunk::new(self.clone().into(), v)),
+                index: 0,
+            }),
+        }
+    }
+
+    /// Return this node's parent as a `Cactus<T>`, which (unlike the `NeCactus` returned by
+    /// `child`) may be empty.
+    pub fn parent(&self) -> Cactus<T> {
+        if self.0.index == 0 {
+            self.0.chunk.parent.clone()
+        } else {
+            Cactus(Some(Inner {
+                chunk: self.0.chunk.clone(),
+                index: self.0.index - 1,
+            }))
+        }
+    }
+
+    /// Return a reference to this node's value.
+    pub fn val(&self) -> &T {
+        self.0.chunk.get(self.0.index)
+    }
+
+    /// How many values are there in this stack? Always at least 1.
+    pub fn len(&self) -> usize {
+        self.0.chunk.base_len + self.0.index + 1
+    }
+
+    /// Always `false`: a `NeCactus` is never empty.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Return an iterator over this node's values, from the top of the stack to the bottom.
+    pub fn vals(&self) -> Vals<'_, T> {
+        Vals {
+            cur: self.clone().into(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for NeCactus<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.val()
+    }
+}
+
+impl<T> Clone for NeCactus<T> {
+    fn clone(&self) -> Self {
+        NeCactus(self.0.clone())
+    }
+}
+
+impl<T> PartialEq for NeCactus<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.index == other.0.index && Arc::ptr_eq(&self.0.chunk, &other.0.chunk)
+    }
+}
+
+impl<T> Eq for NeCactus<T> {}
+
+impl<T> From<NeCactus<T>> for Cactus<T> {
+    fn from(ne: NeCactus<T>) -> Self {
+        Cactus(Some(ne.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // These tests exercise the chunk's unsafe write-once slots, its hand-written `Drop`, and the
+    // `unsafe impl Send/Sync`, so they are good candidates to re-run under Miri.
+
+    #[test]
+    fn deep_linear_stack_crosses_chunk_boundaries() {
+        let n = CHUNK_SIZE * 3 + 5;
+        let mut c = Cactus::new();
+        for i in 0..n {
+            c = c.child(i);
+        }
+        assert_eq!(c.len(), n);
+        let vals: Vec<usize> = c.vals().cloned().collect();
+        assert_eq!(vals, (0..n).rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn branching_from_a_non_top_index_falls_back_to_a_new_chunk() {
+        let mut base = Cactus::new();
+        for i in 0..5 {
+            base = base.child(i);
+        }
+        let mid = base.parent().unwrap().parent().unwrap();
+
+        let a = mid.child(100);
+        let b = mid.child(200);
+        assert_ne!(a, b);
+        assert_eq!(a.parent().unwrap(), mid);
+        assert_eq!(b.parent().unwrap(), mid);
+        assert_eq!(a.len(), mid.len() + 1);
+        assert_eq!(b.len(), mid.len() + 1);
+    }
+
+    #[test]
+    fn drop_only_runs_over_filled_slots() {
+        struct DropCounter(Arc<std::sync::atomic::AtomicUsize>);
+        impl Drop for DropCounter {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let count = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        {
+            let mut c = Cactus::new();
+            for _ in 0..3 {
+                c = c.child(DropCounter(count.clone()));
+            }
+            assert_eq!(count.load(Ordering::SeqCst), 0);
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn concurrent_try_extend_claims_each_slot_exactly_once() {
+        // Race many threads to extend the same node; exactly one should win the in-place
+        // extension and the rest should fall back to fresh chunks, but every value must survive
+        // and be reachable from a distinct node.
+        let base = Cactus::new().child(0);
+        let children: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = (1..=8)
+                .map(|i| {
+                    let base = base.clone();
+                    scope.spawn(move || base.child(i))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let mut seen: Vec<i32> = children.iter().map(|c| *c.val().unwrap()).collect();
+        seen.sort_unstable();
+        assert_eq!(seen, (1..=8).collect::<Vec<_>>());
+        for (i, c) in children.iter().enumerate() {
+            for (j, other) in children.iter().enumerate() {
+                if i != j {
+                    assert_ne!(c, other);
+                }
+            }
+            assert_eq!(c.parent().unwrap(), base);
+        }
+    }
+
+    #[test]
+    fn lca_of_siblings_is_their_parent() {
+        let base = Cactus::new().child(1).child(2);
+        let a = base.child(10);
+        let b = base.child(20);
+        assert_eq!(a.lca(&b), Some(base.clone()));
+        assert_eq!(a.shared_len(&b), base.len());
+    }
+
+    #[test]
+    fn lca_of_ancestor_and_descendant_is_the_ancestor() {
+        let base = Cactus::new().child(1);
+        let descendant = base.child(2).child(3);
+        assert_eq!(base.lca(&descendant), Some(base.clone()));
+        assert_eq!(descendant.lca(&base), Some(base));
+    }
+
+    #[test]
+    fn lca_of_equal_nodes_is_itself() {
+        let c = Cactus::new().child(1).child(2);
+        assert_eq!(c.lca(&c), Some(c));
+    }
+
+    #[test]
+    fn lca_of_disjoint_stacks_is_none() {
+        let a = Cactus::new().child(1).child(2);
+        let b: Cactus<i32> = Cactus::new().child(99);
+        assert_eq!(a.lca(&b), None);
+        assert_eq!(a.shared_len(&b), 0);
+    }
+
+    #[test]
+    fn lca_with_an_empty_cactus_is_none() {
+        let a = Cactus::new().child(1);
+        let empty: Cactus<i32> = Cactus::new();
+        assert_eq!(a.lca(&empty), None);
+        assert_eq!(empty.lca(&a), None);
+    }
+}
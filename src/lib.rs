@@ -33,17 +33,38 @@
 //! There are two flavours of cactus:
 //!
 //! * The standard [`Cactus`](struct.Cactus.html) uses
-//! [`Rc`](https://doc.rust-lang.org/std/rc/struct.Rc.html) internally which makes it well suited
-//! to single-threaded programs but unsuited to multi-threaded programs.
+//!   [`Rc`](https://doc.rust-lang.org/std/rc/struct.Rc.html) internally which makes it well suited
+//!   to single-threaded programs but unsuited to multi-threaded programs.
 //!
 //! * The alternative [`ArcCactus`](struct.ArcCactus.html) uses
-//! [`Arc`](https://doc.rust-lang.org/std/sync/struct.Arc.html) internally which makes it suitable
-//! for multi-threaded programs but potentially slower on single-threaded programs.
+//!   [`Arc`](https://doc.rust-lang.org/std/sync/struct.Arc.html) internally which makes it suitable
+//!   for multi-threaded programs but potentially slower on single-threaded programs.
 //!
 //! Both flavours can be used within a single program.
+//!
+//! For workloads that build and discard huge numbers of states -- backtracking parsers, VM
+//! continuations -- [`CactusArena`](struct.CactusArena.html) and
+//! [`ArcCactusArena`](struct.ArcCactusArena.html) offer an alternative: all nodes live in one
+//! arena and are addressed by cheap, `Copy` handles, trading per-node reclamation for bulk
+//! deallocation when the arena is dropped.
+//!
+//! Neither flavour above supports downward traversal, since a node has no way of knowing what
+//! children (if any) were branched from it. [`TrackedCactus`](struct.TrackedCactus.html) is an
+//! opt-in flavour which tracks that information, at the cost of each node maintaining a list of
+//! `Weak` references to its children.
 
+mod arc_arena;
 mod arc_cactus;
+mod rc_arena;
 mod rc_cactus;
+mod tracked_cactus;
 
+pub use arc_arena::ArcCactusArena;
+pub use arc_arena::Handle as ArcCactusHandle;
 pub use arc_cactus::Cactus as ArcCactus;
+pub use arc_cactus::NeCactus as ArcNeCactus;
+pub use rc_arena::CactusArena;
+pub use rc_arena::Handle as CactusHandle;
 pub use rc_cactus::Cactus;
+pub use rc_cactus::NeCactus;
+pub use tracked_cactus::TrackedCactus;
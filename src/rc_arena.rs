@@ -0,0 +1,253 @@
+//! An arena-backed cactus stack for single-threaded programs.
+//!
+//! Unlike [`Cactus`](../rc_cactus/struct.Cactus.html), which reclaims its nodes chunk by chunk via
+//! `Rc` refcounting, a `CactusArena` owns all of its nodes in one `Vec` and hands out cheap,
+//! `Copy` [`Handle`]s into it. This suits workloads -- backtracking parsers, VM continuations --
+//! that build and discard huge numbers of states, since the whole arena (and hence every node it
+//! contains) can be freed in one go when it is dropped. To reclaim dead sub-stacks before then,
+//! use [`CactusArena::gc`].
+
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_ARENA_ID: AtomicUsize = AtomicUsize::new(0);
+
+struct Node<T> {
+    val: T,
+    parent: Option<usize>,
+}
+
+/// An arena owning a collection of cactus stack nodes.
+pub struct CactusArena<T> {
+    id: usize,
+    nodes: Vec<Node<T>>,
+}
+
+impl<T> CactusArena<T> {
+    /// Create a new, empty arena.
+    pub fn new() -> Self {
+        CactusArena {
+            id: NEXT_ARENA_ID.fetch_add(1, Ordering::Relaxed),
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Return a handle to this arena's (empty) root node.
+    ///
+    /// ```
+    /// use cactus::CactusArena;
+    /// let mut arena = CactusArena::new();
+    /// let root = arena.root();
+    /// assert!(root.is_empty());
+    /// let c1 = root.child(&mut arena, 1);
+    /// assert_eq!(*c1.val(&arena).unwrap(), 1);
+    /// ```
+    pub fn root(&self) -> Handle<T> {
+        Handle {
+            arena_id: self.id,
+            index: None,
+            phantom: PhantomData,
+        }
+    }
+
+    fn check(&self, h: Handle<T>) {
+        assert_eq!(
+            h.arena_id, self.id,
+            "handle does not belong to this arena"
+        );
+    }
+
+    /// Reclaim every node not reachable from `live`, compacting the arena's storage. Returns the
+    /// handles in `live` updated to refer to their (possibly moved) new locations; any other
+    /// handle obtained from this arena before the call is invalidated.
+    pub fn gc(&mut self, live: &[Handle<T>]) -> Vec<Handle<T>> {
+        for h in live {
+            self.check(*h);
+        }
+        let mut reachable = vec![false; self.nodes.len()];
+        for h in live {
+            let mut cur = h.index;
+            while let Some(idx) = cur {
+                if reachable[idx] {
+                    break;
+                }
+                reachable[idx] = true;
+                cur = self.nodes[idx].parent;
+            }
+        }
+        let mut remap = vec![None; self.nodes.len()];
+        let mut new_nodes = Vec::new();
+        for (old_idx, node) in self.nodes.drain(..).enumerate() {
+            if reachable[old_idx] {
+                remap[old_idx] = Some(new_nodes.len());
+                let new_parent = node
+                    .parent
+                    .map(|p| remap[p].expect("a reachable node's parent must also be reachable"));
+                new_nodes.push(Node {
+                    val: node.val,
+                    parent: new_parent,
+                });
+            }
+        }
+        self.nodes = new_nodes;
+        live.iter()
+            .map(|h| Handle {
+                arena_id: h.arena_id,
+                index: h.index.map(|i| remap[i].unwrap()),
+                phantom: PhantomData,
+            })
+            .collect()
+    }
+}
+
+impl<T> Default for CactusArena<T> {
+    fn default() -> Self {
+        CactusArena::new()
+    }
+}
+
+/// A cheap, `Copy` handle to a node owned by a [`CactusArena`]. Every method that takes an arena
+/// panics if given a handle from a different arena.
+pub struct Handle<T> {
+    arena_id: usize,
+    index: Option<usize>,
+    phantom: PhantomData<T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.arena_id == other.arena_id && self.index == other.index
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Handle<T> {
+    /// Is this handle's node empty (i.e. the arena's root)?
+    pub fn is_empty(&self) -> bool {
+        self.index.is_none()
+    }
+
+    /// Create a new node which is a child of this node.
+    pub fn child(&self, arena: &mut CactusArena<T>, v: T) -> Handle<T> {
+        arena.check(*self);
+        arena.nodes.push(Node {
+            val: v,
+            parent: self.index,
+        });
+        Handle {
+            arena_id: self.arena_id,
+            index: Some(arena.nodes.len() - 1),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Return this node's parent, or `None` if this node is empty.
+    pub fn parent(&self, arena: &CactusArena<T>) -> Option<Handle<T>> {
+        arena.check(*self);
+        let idx = self.index?;
+        Some(Handle {
+            arena_id: self.arena_id,
+            index: arena.nodes[idx].parent,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Return a reference to this node's value, or `None` if this node is empty.
+    pub fn val<'a>(&self, arena: &'a CactusArena<T>) -> Option<&'a T> {
+        arena.check(*self);
+        self.index.map(|idx| &arena.nodes[idx].val)
+    }
+
+    /// How many values are there in this stack?
+    pub fn len(&self, arena: &CactusArena<T>) -> usize {
+        arena.check(*self);
+        let mut n = 0;
+        let mut cur = self.index;
+        while let Some(idx) = cur {
+            n += 1;
+            cur = arena.nodes[idx].parent;
+        }
+        n
+    }
+
+    /// Return an iterator over this node's values, from the top of the stack to the bottom.
+    pub fn vals<'a>(&self, arena: &'a CactusArena<T>) -> Vals<'a, T> {
+        arena.check(*self);
+        Vals {
+            arena,
+            cur: self.index,
+        }
+    }
+}
+
+/// An iterator over the values of a `Handle`, from top to bottom. Created by
+/// [`Handle::vals`].
+pub struct Vals<'a, T> {
+    arena: &'a CactusArena<T>,
+    cur: Option<usize>,
+}
+
+impl<'a, T> Iterator for Vals<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let idx = self.cur?;
+        let node = &self.arena.nodes[idx];
+        self.cur = node.parent;
+        Some(&node.val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gc_reclaims_dead_sub_stacks_and_remaps_live_handles() {
+        let mut arena = CactusArena::new();
+        let root = arena.root();
+        let live_branch = root.child(&mut arena, 1).child(&mut arena, 2);
+        // A sibling sub-stack that will not be passed to `gc`, so it should be reclaimed.
+        let _dead_branch = root.child(&mut arena, 10).child(&mut arena, 11).child(&mut arena, 12);
+        let live_leaf = live_branch.child(&mut arena, 3);
+
+        let remapped = arena.gc(&[live_branch, live_leaf]);
+        let new_branch = remapped[0];
+        let new_leaf = remapped[1];
+
+        assert_eq!(new_branch.vals(&arena).collect::<Vec<_>>(), [&2, &1]);
+        assert_eq!(new_branch.len(&arena), 2);
+        assert_eq!(new_leaf.vals(&arena).collect::<Vec<_>>(), [&3, &2, &1]);
+        assert_eq!(new_leaf.len(&arena), 3);
+        assert!(new_leaf.parent(&arena) == Some(new_branch));
+
+        // Only the live sub-stack's three nodes survive the compaction; the dead branch's three
+        // nodes are reclaimed.
+        assert_eq!(arena.nodes.len(), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn gc_invalidates_handles_not_passed_as_live() {
+        let mut arena = CactusArena::new();
+        let root = arena.root();
+        let keep = root.child(&mut arena, 1);
+        let stale = root.child(&mut arena, 2).child(&mut arena, 3);
+
+        arena.gc(&[keep]);
+
+        // `stale` was not kept alive by the `gc` call above, so using it against the
+        // post-compaction arena is out of bounds, as the documented "invalidated" semantics
+        // promise.
+        stale.len(&arena);
+    }
+}
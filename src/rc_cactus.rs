@@ -0,0 +1,486 @@
+//! The `Rc`-based cactus stack, suited to single-threaded programs.
+//!
+//! Internally, nodes are packed into fixed-size chunks: pushing onto a handle that is the
+//! most-recently-created child of its chunk extends that chunk in place rather than allocating a
+//! new `Rc`. This keeps a long, non-branching stack contiguous in memory, while branching (calling
+//! `child` more than once from the same node) transparently falls back to starting a fresh chunk.
+
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+use std::ptr;
+use std::rc::Rc;
+
+/// The number of elements packed into a single chunk allocation.
+const CHUNK_SIZE: usize = 16;
+
+/// An immutable parent pointer tree node ("cactus stack"). See the
+/// [module documentation](index.html) for more details.
+pub struct Cactus<T>(Option<Inner<T>>);
+
+struct Inner<T> {
+    chunk: Rc<Chunk<T>>,
+    /// The index within `chunk` that this handle's logical top occupies.
+    index: usize,
+}
+
+impl<T> Clone for Inner<T> {
+    fn clone(&self) -> Self {
+        Inner {
+            chunk: self.chunk.clone(),
+            index: self.index,
+        }
+    }
+}
+
+/// A single allocation holding up to `CHUNK_SIZE` values. A handle's `index` names its position within
+/// `slots`; slots are written at most once (the "write-once" invariant: a given `(chunk, index)`
+/// pair always denotes the same value) and are claimed via a compare-and-set on `high_water`.
+struct Chunk<T> {
+    parent: Cactus<T>,
+    /// The length of `parent` -- i.e. how many values lie below slot 0 of this chunk.
+    base_len: usize,
+    /// One past the highest slot index claimed so far.
+    high_water: Cell<usize>,
+    slots: [UnsafeCell<MaybeUninit<T>>; CHUNK_SIZE],
+}
+
+impl<T> Chunk<T> {
+    fn new(parent: Cactus<T>, v: T) -> Self {
+        let base_len = parent.len();
+        let slots: [UnsafeCell<MaybeUninit<T>>; CHUNK_SIZE] = [(); CHUNK_SIZE].map(|_| UnsafeCell::new(MaybeUninit::uninit()));
+        unsafe {
+            (*slots[0].get()).write(v);
+        }
+        Chunk {
+            parent,
+            base_len,
+            high_water: Cell::new(1),
+            slots,
+        }
+    }
+
+    /// Tries to claim slot `index + 1` for `v`. Fails (returning `v` back) if another child has
+    /// already claimed that slot or the chunk is full, in which case the caller must start a new
+    /// chunk instead.
+    fn try_extend(&self, index: usize, v: T) -> Result<(), T> {
+        let next = index + 1;
+        if next >= CHUNK_SIZE || self.high_water.get() != next {
+            return Err(v);
+        }
+        self.high_water.set(next + 1);
+        unsafe {
+            (*self.slots[next].get()).write(v);
+        }
+        Ok(())
+    }
+
+    fn get(&self, index: usize) -> &T {
+        debug_assert!(index < self.high_water.get());
+        unsafe { (*self.slots[index].get()).assume_init_ref() }
+    }
+}
+
+impl<T> Drop for Chunk<T> {
+    fn drop(&mut self) {
+        for i in 0..self.high_water.get() {
+            unsafe {
+                ptr::drop_in_place(self.slots[i].get_mut().as_mut_ptr());
+            }
+        }
+    }
+}
+
+impl<T> Cactus<T> {
+    /// Create an empty cactus stack.
+    ///
+    /// ```
+    /// use cactus::Cactus;
+    /// let c = Cactus::<u8>::new();
+    /// assert!(c.is_empty());
+    /// ```
+    pub fn new() -> Self {
+        Cactus(None)
+    }
+
+    /// Is this cactus empty?
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Create a new node which is a child of this node.
+    pub fn child(&self, v: T) -> Cactus<T> {
+        match &self.0 {
+            None => Cactus(Some(Inner {
+                chunk: Rc::new(Chunk::new(Cactus::new(), v)),
+                index: 0,
+            })),
+            Some(inner) => match inner.chunk.try_extend(inner.index, v) {
+                Ok(()) => Cactus(Some(Inner {
+                    chunk: inner.chunk.clone(),
+                    index: inner.index + 1,
+                })),
+                Err(v) => Cactus(Some(Inner {
+                    chunk: Rc::new(Chunk::new(self.clone(), v)),
+                    index: 0,
+                })),
+            },
+        }
+    }
+
+    /// Return this node's parent, or `None` if this node is empty.
+    pub fn parent(&self) -> Option<Cactus<T>> {
+        self.0.as_ref().map(|inner| {
+            if inner.index == 0 {
+                inner.chunk.parent.clone()
+            } else {
+                Cactus(Some(Inner {
+                    chunk: inner.chunk.clone(),
+                    index: inner.index - 1,
+                }))
+            }
+        })
+    }
+
+    /// Return a reference to this node's value, or `None` if this node is empty.
+    pub fn val(&self) -> Option<&T> {
+        self.0.as_ref().map(|inner| inner.chunk.get(inner.index))
+    }
+
+    /// How many values are there in this stack?
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            None => 0,
+            Some(inner) => inner.chunk.base_len + inner.index + 1,
+        }
+    }
+
+    /// Return an iterator over this node's values, from the top of the stack to the bottom.
+    pub fn vals(&self) -> Vals<'_, T> {
+        Vals {
+            cur: self.clone(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Return the deepest node shared by `self` and `other`, or `None` if the two stacks have no
+    /// common ancestor.
+    ///
+    /// ```
+    /// use cactus::Cactus;
+    /// let c = Cactus::new().child(1);
+    /// let c2 = c.child(2);
+    /// let c3 = c.child(3);
+    /// assert_eq!(c2.lca(&c3), Some(c));
+    /// ```
+    pub fn lca(&self, other: &Cactus<T>) -> Option<Cactus<T>> {
+        let mut a = self.clone();
+        let mut b = other.clone();
+        let mut a_len = a.len();
+        let mut b_len = b.len();
+        while a_len > b_len {
+            a = a.parent().unwrap();
+            a_len -= 1;
+        }
+        while b_len > a_len {
+            b = b.parent().unwrap();
+            b_len -= 1;
+        }
+        while a != b {
+            a = a.parent().unwrap();
+            b = b.parent().unwrap();
+        }
+        if a.is_empty() {
+            None
+        } else {
+            Some(a)
+        }
+    }
+
+    /// The length of the common suffix `self` and `other` share, i.e. `self.lca(other).len()`.
+    pub fn shared_len(&self, other: &Cactus<T>) -> usize {
+        self.lca(other).map_or(0, |c| c.len())
+    }
+
+    /// Try to convert this cactus into a [`NeCactus`], which statically guarantees it has a
+    /// value at its top. Returns the original (empty) cactus back in `Err` if it has none.
+    ///
+    /// ```
+    /// use cactus::Cactus;
+    /// let c = Cactus::new().child(1);
+    /// let nec = c.into_nonempty().unwrap();
+    /// assert_eq!(*nec, 1);
+    /// ```
+    pub fn into_nonempty(self) -> Result<NeCactus<T>, Cactus<T>> {
+        match self.0 {
+            Some(inner) => Ok(NeCactus(inner)),
+            None => Err(Cactus(None)),
+        }
+    }
+}
+
+impl<T> Default for Cactus<T> {
+    fn default() -> Self {
+        Cactus::new()
+    }
+}
+
+impl<T> Clone for Cactus<T> {
+    fn clone(&self) -> Self {
+        Cactus(self.0.clone())
+    }
+}
+
+impl<T> PartialEq for Cactus<T> {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (None, None) => true,
+            (Some(a), Some(b)) => a.index == b.index && Rc::ptr_eq(&a.chunk, &b.chunk),
+            _ => false,
+        }
+    }
+}
+
+impl<T> Eq for Cactus<T> {}
+
+impl<T: fmt::Debug> fmt::Debug for Cactus<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cactus {{ ")?;
+        let mut first = true;
+        for v in self.vals() {
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}", v)?;
+            first = false;
+        }
+        write!(f, " }}")
+    }
+}
+
+/// An iterator over the values of a `Cactus`, from top to bottom. Created by
+/// [`Cactus::vals`](struct.Cactus.html#method.vals).
+pub struct Vals<'a, T> {
+    cur: Cactus<T>,
+    phantom: PhantomData<&'a T>,
+}
+
+impl<'a, T> Iterator for Vals<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let inner = self.cur.0.as_ref()?;
+        let val = inner.chunk.get(inner.index) as *const T;
+        self.cur = self.cur.parent().unwrap();
+        // Safe because the original `Cactus` that `vals` was called on keeps every chunk in this
+        // stack's parent chain alive for at least as long as `'a`, regardless of where `self.cur`
+        // has since moved on to.
+        Some(unsafe { &*val })
+    }
+}
+
+/// A cactus stack node which is statically guaranteed to have a value at its top, eliminating the
+/// need to unwrap [`Cactus::val`]. Obtained via [`Cactus::into_nonempty`] or [`NeCactus::child`].
+/// Derefs to its top value.
+pub struct NeCactus<T>(Inner<T>);
+
+impl<T> NeCactus<T> {
+    /// Create a new node which is a child of this node.
+    pub fn child(&self, v: T) -> NeCactus<T> {
+        match self.0.chunk.try_extend(self.0.index, v) {
+            Ok(()) => NeCactus(Inner {
+                chunk: self.0.chunk.clone(),
+                index: self.0.index + 1,
+            }),
+            Err(v) => NeCactus(Inner {
+                chunk: Rc::new(Chunk::new(self.clone().into(), v)),
+                index: 0,
+            }),
+        }
+    }
+
+    /// Return this node's parent as a `Cactus<T>`, which (unlike the `NeCactus` returned by
+    /// `child`) may be empty.
+    pub fn parent(&self) -> Cactus<T> {
+        if self.0.index == 0 {
+            self.0.chunk.parent.clone()
+        } else {
+            Cactus(Some(Inner {
+                chunk: self.0.chunk.clone(),
+                index: self.0.index - 1,
+            }))
+        }
+    }
+
+    /// Return a reference to this node's value.
+    pub fn val(&self) -> &T {
+        self.0.chunk.get(self.0.index)
+    }
+
+    /// How many values are there in this stack? Always at least 1.
+    pub fn len(&self) -> usize {
+        self.0.chunk.base_len + self.0.index + 1
+    }
+
+    /// Always `false`: a `NeCactus` is never empty.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Return an iterator over this node's values, from the top of the stack to the bottom.
+    pub fn vals(&self) -> Vals<'_, T> {
+        Vals {
+            cur: self.clone().into(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for NeCactus<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.val()
+    }
+}
+
+impl<T> Clone for NeCactus<T> {
+    fn clone(&self) -> Self {
+        NeCactus(self.0.clone())
+    }
+}
+
+impl<T> PartialEq for NeCactus<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.index == other.0.index && Rc::ptr_eq(&self.0.chunk, &other.0.chunk)
+    }
+}
+
+impl<T> Eq for NeCactus<T> {}
+
+impl<T> From<NeCactus<T>> for Cactus<T> {
+    fn from(ne: NeCactus<T>) -> Self {
+        Cactus(Some(ne.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // These tests exercise the chunk's unsafe write-once slots and its hand-written `Drop`, so
+    // they are good candidates to re-run under Miri.
+
+    #[test]
+    fn deep_linear_stack_crosses_chunk_boundaries() {
+        let n = CHUNK_SIZE * 3 + 5;
+        let mut c = Cactus::new();
+        for i in 0..n {
+            c = c.child(i);
+        }
+        assert_eq!(c.len(), n);
+        let vals: Vec<usize> = c.vals().cloned().collect();
+        assert_eq!(vals, (0..n).rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn branching_from_a_non_top_index_falls_back_to_a_new_chunk() {
+        // Push far enough to claim several slots in the root chunk, then rewind to an index
+        // that is no longer the chunk's high-water mark before branching from it.
+        let mut base = Cactus::new();
+        for i in 0..5 {
+            base = base.child(i);
+        }
+        let mid = base.parent().unwrap().parent().unwrap();
+
+        let a = mid.child(100);
+        let b = mid.child(200);
+        assert_ne!(a, b);
+        assert_eq!(a.parent().unwrap(), mid);
+        assert_eq!(b.parent().unwrap(), mid);
+        assert_eq!(a.len(), mid.len() + 1);
+        assert_eq!(b.len(), mid.len() + 1);
+
+        // `base`'s own descendants are untouched by the branch above.
+        let c = base.child(300);
+        assert_eq!(c.vals().cloned().collect::<Vec<_>>(), {
+            let mut v = vec![300];
+            v.extend(base.vals().cloned());
+            v
+        });
+    }
+
+    #[test]
+    fn drop_only_runs_over_filled_slots() {
+        struct DropCounter(Rc<Cell<usize>>);
+        impl Drop for DropCounter {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let count = Rc::new(Cell::new(0));
+        {
+            let mut c = Cactus::new();
+            // Fill only part of a chunk, well short of `CHUNK_SIZE`.
+            for _ in 0..3 {
+                c = c.child(DropCounter(count.clone()));
+            }
+            assert_eq!(count.get(), 0);
+        }
+        assert_eq!(count.get(), 3);
+    }
+
+    #[test]
+    fn lca_of_siblings_is_their_parent() {
+        let base = Cactus::new().child(1).child(2);
+        let a = base.child(10);
+        let b = base.child(20);
+        assert_eq!(a.lca(&b), Some(base.clone()));
+        assert_eq!(a.shared_len(&b), base.len());
+    }
+
+    #[test]
+    fn lca_of_ancestor_and_descendant_is_the_ancestor() {
+        let base = Cactus::new().child(1);
+        let descendant = base.child(2).child(3);
+        assert_eq!(base.lca(&descendant), Some(base.clone()));
+        assert_eq!(descendant.lca(&base), Some(base));
+    }
+
+    #[test]
+    fn lca_of_equal_nodes_is_itself() {
+        let c = Cactus::new().child(1).child(2);
+        assert_eq!(c.lca(&c), Some(c));
+    }
+
+    #[test]
+    fn lca_of_disjoint_stacks_is_none() {
+        let a = Cactus::new().child(1).child(2);
+        let b: Cactus<i32> = Cactus::new().child(99);
+        assert_eq!(a.lca(&b), None);
+        assert_eq!(a.shared_len(&b), 0);
+    }
+
+    #[test]
+    fn lca_with_an_empty_cactus_is_none() {
+        let a = Cactus::new().child(1);
+        let empty: Cactus<i32> = Cactus::new();
+        assert_eq!(a.lca(&empty), None);
+        assert_eq!(empty.lca(&a), None);
+    }
+
+    #[test]
+    fn lca_crosses_chunk_boundaries() {
+        let mut base = Cactus::new();
+        for i in 0..(CHUNK_SIZE + 3) {
+            base = base.child(i);
+        }
+        let a = base.child(1000).child(1001);
+        let b = base.child(2000);
+        assert_eq!(a.lca(&b), Some(base.clone()));
+        assert_eq!(a.shared_len(&b), base.len());
+    }
+}
@@ -0,0 +1,165 @@
+//! An opt-in cactus stack flavour that also supports downward traversal.
+//!
+//! The parent-pointer design of [`Cactus`](../rc_cactus/struct.Cactus.html) means a node can't
+//! enumerate the sub-stacks branched from it. `TrackedCactus` adds this by having each node keep
+//! a list of `Weak` references to the children created from it. Because the references are
+//! `Weak`, tracking never keeps a node alive longer than an untracked cactus would: once a child
+//! becomes unreachable it simply disappears from its parent's list.
+
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+/// An immutable parent pointer tree node which also tracks its children. See the
+/// [module documentation](index.html) for more details.
+pub struct TrackedCactus<T>(Option<Rc<Node<T>>>);
+
+struct Node<T> {
+    val: T,
+    parent: TrackedCactus<T>,
+    children: RefCell<Vec<Weak<Node<T>>>>,
+}
+
+impl<T> Clone for TrackedCactus<T> {
+    fn clone(&self) -> Self {
+        TrackedCactus(self.0.clone())
+    }
+}
+
+impl<T> PartialEq for TrackedCactus<T> {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (None, None) => true,
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl<T> Eq for TrackedCactus<T> {}
+
+impl<T> Default for TrackedCactus<T> {
+    fn default() -> Self {
+        TrackedCactus::new()
+    }
+}
+
+impl<T> TrackedCactus<T> {
+    /// Create an empty cactus stack.
+    pub fn new() -> Self {
+        TrackedCactus(None)
+    }
+
+    /// Is this cactus empty?
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Create a new node which is a child of this node.
+    ///
+    /// ```
+    /// use cactus::TrackedCactus;
+    /// let c = TrackedCactus::new().child(1);
+    /// let c2 = c.child(2);
+    /// let c3 = c.child(3);
+    /// assert_eq!(c.children().count(), 2);
+    /// ```
+    pub fn child(&self, v: T) -> TrackedCactus<T> {
+        let node = Rc::new(Node {
+            val: v,
+            parent: self.clone(),
+            children: RefCell::new(Vec::new()),
+        });
+        if let Some(parent) = &self.0 {
+            let mut children = parent.children.borrow_mut();
+            // Compact away dead entries here rather than leaving them for `children`/`descendants`
+            // to filter out each time, so a node that repeatedly creates and drops children doesn't
+            // accumulate an unbounded number of stale `Weak`s.
+            children.retain(|weak| weak.strong_count() > 0);
+            children.push(Rc::downgrade(&node));
+        }
+        TrackedCactus(Some(node))
+    }
+
+    /// Return this node's parent, or `None` if this node is empty.
+    pub fn parent(&self) -> Option<TrackedCactus<T>> {
+        self.0.as_ref().map(|node| node.parent.clone())
+    }
+
+    /// Return a reference to this node's value, or `None` if this node is empty.
+    pub fn val(&self) -> Option<&T> {
+        self.0.as_ref().map(|node| &node.val)
+    }
+
+    /// How many values are there in this stack?
+    pub fn len(&self) -> usize {
+        let mut n = 0;
+        let mut cur = self.clone();
+        while !cur.is_empty() {
+            n += 1;
+            cur = cur.parent().unwrap();
+        }
+        n
+    }
+
+    /// Return an iterator over the still-live children directly branched from this node. An empty
+    /// cactus does not track the children created from it, so for it this yields nothing.
+    pub fn children(&self) -> impl Iterator<Item = TrackedCactus<T>> + '_ {
+        self.0.iter().flat_map(|node| {
+            node.children
+                .borrow()
+                .iter()
+                .filter_map(|weak| weak.upgrade().map(TrackedCactus::from_rc))
+                .collect::<Vec<_>>()
+                .into_iter()
+        })
+    }
+
+    /// Return a pre-order iterator over every still-live node reachable downwards from this node
+    /// (including this node itself, unless it is empty).
+    pub fn descendants(&self) -> Descendants<T> {
+        Descendants {
+            stack: if self.is_empty() {
+                Vec::new()
+            } else {
+                vec![self.clone()]
+            },
+        }
+    }
+
+    fn from_rc(node: Rc<Node<T>>) -> TrackedCactus<T> {
+        TrackedCactus(Some(node))
+    }
+}
+
+/// A pre-order iterator over a `TrackedCactus`'s descendants. Created by
+/// [`TrackedCactus::descendants`].
+pub struct Descendants<T> {
+    stack: Vec<TrackedCactus<T>>,
+}
+
+impl<T> Iterator for Descendants<T> {
+    type Item = TrackedCactus<T>;
+
+    fn next(&mut self) -> Option<TrackedCactus<T>> {
+        let cur = self.stack.pop()?;
+        // Push in reverse so that children are visited in creation order.
+        self.stack.extend(cur.children().collect::<Vec<_>>().into_iter().rev());
+        Some(cur)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_children_do_not_accumulate_unboundedly() {
+        let parent = TrackedCactus::new().child(0);
+        for i in 0..1000 {
+            // Each child is dropped immediately, so only the most recently created one should
+            // ever be retained as a dead `Weak` alongside it.
+            parent.child(i);
+        }
+        assert!(parent.0.as_ref().unwrap().children.borrow().len() <= 1);
+    }
+}